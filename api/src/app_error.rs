@@ -0,0 +1,62 @@
+use crate::storage_error::StorageError;
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+
+/// Crate-wide HTTP error type: every handler returns `Result<_, AppError>` and lets `?`
+/// do the conversion instead of hand-matching on the underlying repo/domain error.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("not found")]
+    NotFound,
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("forbidden")]
+    Forbidden,
+    #[error(transparent)]
+    Internal(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: &'static str,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, code) = match &self {
+            AppError::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+            AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, "bad_request"),
+            AppError::Conflict(_) => (StatusCode::CONFLICT, "conflict"),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+            AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+
+        // `Internal` wraps raw backend errors (sqlx/firestore) which may carry connection
+        // strings or query details - log them server-side and keep the response generic.
+        let message = if let AppError::Internal(err) = &self {
+            tracing::error!(error = %err, "internal error");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+
+        (status, Json(ErrorBody { error: message, code })).into_response()
+    }
+}
+
+impl From<StorageError> for AppError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::NotFound => AppError::NotFound,
+            StorageError::Conflict(msg) => AppError::Conflict(msg),
+            StorageError::InvalidScore(err) => AppError::BadRequest(err.to_string()),
+            StorageError::Backend(err) => AppError::Internal(err),
+        }
+    }
+}
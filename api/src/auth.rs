@@ -0,0 +1,118 @@
+use crate::app_error::AppError;
+use crate::AppState;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::Response,
+};
+use rand::rngs::OsRng;
+
+/// The project an `x-api-key`/bearer token resolved to, injected in request extensions by
+/// [`require_api_key`] so downstream handlers can scope their queries to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectId(pub String);
+
+/// Hashes the secret half of a freshly-generated API key for storage.
+pub fn hash_api_key_secret(secret: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Internal(Box::new(e)))
+}
+
+fn verify_api_key_secret(secret: &str, hash: &str) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(secret.as_bytes(), &parsed_hash)
+        .is_ok()
+}
+
+/// Axum middleware verifying the `x-api-key` (or `Authorization: Bearer`) header and injecting
+/// the resolved [`ProjectId`] into the request extensions.
+///
+/// Keys are shaped `{key_id}.{secret}`: `key_id` is used to look the [`crate::project::ApiKey`]
+/// record up, `secret` is verified against its argon2 hash.
+pub async fn require_api_key(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let raw = raw_api_key(req.headers()).ok_or(AppError::Unauthorized)?;
+    let (key_id, secret) = parse_api_key(&raw).ok_or(AppError::Unauthorized)?;
+
+    let api_key = state
+        .project_repo
+        .find_api_key(key_id.to_string())
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+
+    if !verify_api_key_secret(secret, &api_key.hash) {
+        return Err(AppError::Unauthorized);
+    }
+
+    req.extensions_mut()
+        .insert(ProjectId(api_key.project_id.clone()));
+
+    Ok(next.run(req).await)
+}
+
+/// Reads the raw `{key_id}.{secret}` token out of `x-api-key`, falling back to a
+/// `Authorization: Bearer` header.
+fn raw_api_key(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get("x-api-key")
+        .or_else(|| headers.get(AUTHORIZATION))
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_start_matches("Bearer ").to_string())
+}
+
+/// Splits a raw `{key_id}.{secret}` token into its two parts.
+fn parse_api_key(raw: &str) -> Option<(&str, &str)> {
+    raw.split_once('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+
+    #[test]
+    fn parse_api_key_splits_on_the_first_dot() {
+        assert_eq!(parse_api_key("key1.sec.ret"), Some(("key1", "sec.ret")));
+        assert_eq!(parse_api_key("no-dot-here"), None);
+    }
+
+    #[test]
+    fn raw_api_key_prefers_x_api_key_over_authorization() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "key1.secret1".parse().unwrap());
+        headers.insert(AUTHORIZATION, "Bearer key2.secret2".parse().unwrap());
+
+        assert_eq!(raw_api_key(&headers), Some("key1.secret1".to_string()));
+    }
+
+    #[test]
+    fn raw_api_key_strips_the_bearer_prefix() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer key1.secret1".parse().unwrap());
+
+        assert_eq!(raw_api_key(&headers), Some("key1.secret1".to_string()));
+    }
+
+    #[test]
+    fn verify_api_key_secret_rejects_a_wrong_secret() {
+        let hash = hash_api_key_secret("correct-secret").unwrap();
+
+        assert!(verify_api_key_secret("correct-secret", &hash));
+        assert!(!verify_api_key_secret("wrong-secret", &hash));
+    }
+}
@@ -1,19 +1,33 @@
-use chrono::{DateTime, Utc};
-use errors::FirestoreError;
+mod app_error;
+mod auth;
+mod project;
+mod rank;
+mod storage_error;
+mod user;
+
+use app_error::AppError;
+use chrono::Utc;
 use firestore::*;
 use metrics::{counter, describe_counter};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use metrics_util::MetricKindMask;
 use nanoid::nanoid;
-use std::{collections::HashMap, net::{IpAddr, Ipv4Addr}, os::unix::net::SocketAddr, sync::{Arc, Mutex}, time::Duration};
+use project::{FirestoreProjectRepo, InMemoryProjectRepo, PostgresProjectRepo, ProjectRepo};
+use rank::{LeaderboardPage, Rank, RankRepo, RankRepoFirestore, RankRepoInMemory, RankRepoPostgres};
+use sqlx::postgres::PgPoolOptions;
+use std::{sync::Arc, time::Duration};
+use storage_error::StorageError;
+use user::{FirestoreUserRepo, InMemoryUserRepo, PostgresUserRepo, User, UserRepo};
 
+use auth::ProjectId;
 use axum::{
-    extract::{Path, State},
+    extract::{Extension, Path, Query, State},
     http::StatusCode,
+    middleware,
     routing::{get, post},
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
 pub fn config_env_var(name: &str) -> Result<String, String> {
     std::env::var(name).map_err(|e| format!("{}: {}", name, e))
@@ -36,15 +50,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .install()
         .expect("failed to install Prometheus recorder");
     describe_counter!("custom", "Just a random metric to check everything is working as expected.");
-    
-    // Create an instance
-    let db: FirestoreDb = FirestoreDb::new(&config_env_var("PROJECT_ID")?).await?;
 
-    let user_repo = FirestoreUserRepo {
-        collection_name: "test".to_string().into(),
-        db: db.into(),
+    let backend = std::env::var("BACKEND").unwrap_or_else(|_| "firestore".to_string());
+    let (user_repo, rank_repo, project_repo): (
+        Arc<dyn UserRepo>,
+        Arc<dyn RankRepo>,
+        Arc<dyn ProjectRepo>,
+    ) = match backend.as_str() {
+        "memory" => (
+            Arc::new(InMemoryUserRepo::default()),
+            Arc::new(RankRepoInMemory::default()),
+            Arc::new(InMemoryProjectRepo::default()),
+        ),
+        "postgres" => {
+            let pool = PgPoolOptions::new()
+                .max_connections(5)
+                .connect(&config_env_var("DATABASE_URL")?)
+                .await?;
+
+            (
+                Arc::new(PostgresUserRepo::new(pool.clone())),
+                Arc::new(RankRepoPostgres::new(pool.clone())),
+                Arc::new(PostgresProjectRepo::new(pool)),
+            )
+        }
+        "firestore" => {
+            let db: Arc<FirestoreDb> =
+                FirestoreDb::new(&config_env_var("PROJECT_ID")?).await?.into();
+
+            (
+                Arc::new(FirestoreUserRepo {
+                    collection_name: "test".to_string().into(),
+                    db: db.clone(),
+                }),
+                Arc::new(RankRepoFirestore::new(db.clone())),
+                Arc::new(FirestoreProjectRepo::new(db)),
+            )
+        }
+        other => return Err(format!("unknown BACKEND: {other}").into()),
+    };
+
+    let state = AppState {
+        user_repo,
+        rank_repo,
+        project_repo,
     };
-    // let user_repo = InMemoryUserRepo::default();
 
     // build our application with a route
     let app = Router::new()
@@ -54,9 +104,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .route("/users", post(create_user))
         .route("/users/:id", get(get_user))
         // .layer() TODO: Middleware (layer) with global generic metrics
-        .with_state(AppState {
-            user_repo: Arc::new(user_repo.clone()),
-        });
+        .nest("/projects/:project_id", projects_router(state.clone()))
+        .with_state(state);
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -70,20 +119,140 @@ async fn root() -> &'static str {
     "Hello, World!"
 }
 
+/// Routes scoped to a single project, gated by [`auth::require_api_key`].
+fn projects_router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/items/:item_id/scores", post(create_score))
+        .route("/items/:item_id/rank", get(get_rank))
+        .route("/leaderboard", get(get_leaderboard))
+        .route_layer(middleware::from_fn_with_state(state, auth::require_api_key))
+}
+
+#[derive(Deserialize)]
+struct ItemPath {
+    project_id: String,
+    item_id: String,
+}
+
+fn ensure_own_project(authenticated: &ProjectId, project_id: &str) -> Result<(), AppError> {
+    if authenticated.0 != project_id {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(())
+}
+
+// the input to our `create_score` handler
+#[derive(Deserialize)]
+struct CreateScore {
+    score: f64,
+    /// only read the first time a given item is scored, to create its `Rank` document
+    min: Option<f64>,
+    /// only read the first time a given item is scored, to create its `Rank` document
+    max: Option<f64>,
+}
+
+async fn create_score(
+    State(state): State<AppState>,
+    Extension(authenticated): Extension<ProjectId>,
+    Path(ItemPath { project_id, item_id }): Path<ItemPath>,
+    Json(payload): Json<CreateScore>,
+) -> Result<StatusCode, AppError> {
+    ensure_own_project(&authenticated, &project_id)?;
+
+    let mut rank = Rank {
+        project_id,
+        item_id,
+        ..Default::default()
+    };
+    rank.compute_id();
+
+    match state.rank_repo.rank(rank.id.clone(), payload.score).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(StorageError::NotFound) => {
+            let (min, max) = match (payload.min, payload.max) {
+                (Some(min), Some(max)) => (min, max),
+                _ => {
+                    return Err(AppError::BadRequest(
+                        "min and max are required to create a new item".to_string(),
+                    ))
+                }
+            };
+
+            rank.min = min;
+            rank.max = max;
+            rank.created_at = Utc::now();
+            rank.update_score(payload.score)
+                .map_err(|err| AppError::BadRequest(err.to_string()))?;
+
+            state.rank_repo.save(&rank).await?;
+
+            Ok(StatusCode::CREATED)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn get_rank(
+    State(state): State<AppState>,
+    Extension(authenticated): Extension<ProjectId>,
+    Path(ItemPath { project_id, item_id }): Path<ItemPath>,
+) -> Result<Json<Rank>, AppError> {
+    ensure_own_project(&authenticated, &project_id)?;
+
+    let id = Rank {
+        project_id,
+        item_id,
+        ..Default::default()
+    }
+    .get_computed_id();
+
+    let rank = state.rank_repo.get(id).await?.ok_or(AppError::NotFound)?;
+
+    Ok(Json(rank))
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+async fn get_leaderboard(
+    State(state): State<AppState>,
+    Extension(authenticated): Extension<ProjectId>,
+    Path(project_id): Path<String>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<LeaderboardPage>, AppError> {
+    ensure_own_project(&authenticated, &project_id)?;
+
+    let project = state
+        .project_repo
+        .get_project(project_id.clone())
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let page = state
+        .rank_repo
+        .leaderboard(
+            project_id,
+            query.limit.unwrap_or(20),
+            query.cursor,
+            project.min_votes,
+        )
+        .await?;
+
+    Ok(Json(page))
+}
+
 async fn get_user(
     State(state): State<AppState>,
     Path(id): Path<String>,
-) -> Result<Json<User>, StatusCode> {
+) -> Result<Json<User>, AppError> {
     counter!("custom", "system" => "foo").increment(1);
-    match state.user_repo.get_user(id).await {
-        Ok(user) => match user {
-            None => Err(StatusCode::NOT_FOUND),
-            Some(user) => Ok(Json(user)),
-        },
-        // TODO: Map FirestoreError to StatusCodes
-        // https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    let user = state.user_repo.get_user(id).await?.ok_or(AppError::NotFound)?;
+
+    Ok(Json(user))
 }
 
 async fn create_user(
@@ -91,7 +260,7 @@ async fn create_user(
     // this argument tells axum to parse the request body
     // as JSON into a `CreateUser` type
     Json(payload): Json<CreateUser>,
-) -> Result<(StatusCode, Json<User>), StatusCode> {
+) -> Result<(StatusCode, Json<User>), AppError> {
     // insert your application logic here
     let id = nanoid!();
     let user = User {
@@ -101,12 +270,9 @@ async fn create_user(
         deleted_at: None,
     };
 
-    match state.user_repo.save_user(&user).await {
-        Ok(_) => Ok((StatusCode::CREATED, Json(user))),
-        // TODO: Map FirestoreError to StatusCodes
-        // https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    state.user_repo.save_user(&user).await?;
+
+    Ok((StatusCode::CREATED, Json(user)))
 }
 
 // the input to our `create_user` handler
@@ -118,72 +284,87 @@ struct CreateUser {
 #[derive(Clone)]
 struct AppState {
     user_repo: Arc<dyn UserRepo>,
+    rank_repo: Arc<dyn RankRepo>,
+    project_repo: Arc<dyn ProjectRepo>,
 }
 
-// the output to our `create_user` handler
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct User {
-    id: String,
-    username: String,
-    created_at: DateTime<Utc>,
-    deleted_at: Option<DateTime<Utc>>,
-}
-
-#[async_trait]
-trait UserRepo: Send + Sync {
-    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, FirestoreError>;
-
-    async fn save_user(&self, user: &User) -> Result<(), FirestoreError>;
-}
-
-#[derive(Debug, Clone, Default)]
-struct InMemoryUserRepo {
-    map: Arc<Mutex<HashMap<String, User>>>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use project::InMemoryProjectRepo;
+    use rank::RankRepoInMemory;
+    use user::InMemoryUserRepo;
 
-#[async_trait]
-impl UserRepo for InMemoryUserRepo {
-    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, FirestoreError> {
-        Result::Ok(self.map.lock().unwrap().get(&id).cloned())
+    fn in_memory_state() -> AppState {
+        AppState {
+            user_repo: Arc::new(InMemoryUserRepo::default()),
+            rank_repo: Arc::new(RankRepoInMemory::default()),
+            project_repo: Arc::new(InMemoryProjectRepo::default()),
+        }
     }
 
-    async fn save_user(&self, user: &User) -> Result<(), FirestoreError> {
-        self.map
-            .lock()
-            .unwrap()
-            .insert(user.id.clone(), user.clone());
+    #[tokio::test]
+    async fn create_score_requires_min_and_max_for_a_new_item() {
+        let state = in_memory_state();
+        let authenticated = ProjectId("project1".to_string());
+        let path = ItemPath {
+            project_id: "project1".to_string(),
+            item_id: "item1".to_string(),
+        };
 
-        Result::Ok(())
+        let result = create_score(
+            State(state),
+            Extension(authenticated),
+            Path(path),
+            Json(CreateScore {
+                score: 4.0,
+                min: None,
+                max: None,
+            }),
+        )
+        .await;
+
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
     }
-}
 
-#[derive(Debug, Clone)]
-struct FirestoreUserRepo {
-    collection_name: Arc<String>,
-    db: Arc<FirestoreDb>,
-}
+    #[tokio::test]
+    async fn create_score_creates_then_updates_an_existing_item() {
+        let state = in_memory_state();
+        let authenticated = ProjectId("project1".to_string());
+        let path = ItemPath {
+            project_id: "project1".to_string(),
+            item_id: "item1".to_string(),
+        };
 
-#[async_trait]
-impl UserRepo for FirestoreUserRepo {
-    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, FirestoreError> {
-        self.db
-            .fluent()
-            .select()
-            .by_id_in(&self.collection_name)
-            .obj()
-            .one(&id)
-            .await
-    }
+        let created = create_score(
+            State(state.clone()),
+            Extension(authenticated.clone()),
+            Path(ItemPath {
+                project_id: path.project_id.clone(),
+                item_id: path.item_id.clone(),
+            }),
+            Json(CreateScore {
+                score: 4.0,
+                min: Some(1.0),
+                max: Some(5.0),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created, StatusCode::CREATED);
 
-    async fn save_user(&self, user: &User) -> Result<(), FirestoreError> {
-        self.db
-            .fluent()
-            .insert()
-            .into(&self.collection_name)
-            .document_id(&user.id)
-            .object(user)
-            .execute()
-            .await
+        let updated = create_score(
+            State(state),
+            Extension(authenticated),
+            Path(path),
+            Json(CreateScore {
+                score: 2.0,
+                min: None,
+                max: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated, StatusCode::NO_CONTENT);
     }
 }
@@ -0,0 +1,222 @@
+use crate::storage_error::StorageError;
+use chrono::{DateTime, Utc};
+use firestore::*;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+static PROJECT_FIRESTORE_COLLECTION: &str = "projects";
+static API_KEY_FIRESTORE_COLLECTION: &str = "api_keys";
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    /// prior weight `m` used by the Bayesian leaderboard score, see `rank::Rank::bayesian_score`
+    pub min_votes: f64,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A hashed API key scoped to a single project.
+///
+/// `id` is the public, non-secret part of the key (sent as-is by the caller so we can look the
+/// record up), `hash` is the argon2 hash of the secret part. The caller-facing key is the
+/// concatenation `{id}.{secret}`.
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: String,
+    pub project_id: String,
+    pub hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait ProjectRepo: Send + Sync {
+    async fn get_project(&self, id: String) -> Result<std::option::Option<Project>, StorageError>;
+
+    async fn save_project(&self, project: &Project) -> Result<(), StorageError>;
+
+    async fn find_api_key(&self, id: String) -> Result<std::option::Option<ApiKey>, StorageError>;
+
+    async fn save_api_key(&self, api_key: &ApiKey) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryProjectRepo {
+    projects: Arc<Mutex<HashMap<String, Project>>>,
+    api_keys: Arc<Mutex<HashMap<String, ApiKey>>>,
+}
+
+#[async_trait]
+impl ProjectRepo for InMemoryProjectRepo {
+    async fn get_project(&self, id: String) -> Result<std::option::Option<Project>, StorageError> {
+        Ok(self.projects.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn save_project(&self, project: &Project) -> Result<(), StorageError> {
+        self.projects
+            .lock()
+            .unwrap()
+            .insert(project.id.clone(), project.clone());
+
+        Ok(())
+    }
+
+    async fn find_api_key(&self, id: String) -> Result<std::option::Option<ApiKey>, StorageError> {
+        Ok(self.api_keys.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn save_api_key(&self, api_key: &ApiKey) -> Result<(), StorageError> {
+        self.api_keys
+            .lock()
+            .unwrap()
+            .insert(api_key.id.clone(), api_key.clone());
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirestoreProjectRepo {
+    db: Arc<FirestoreDb>,
+}
+
+impl FirestoreProjectRepo {
+    pub fn new(db: Arc<FirestoreDb>) -> Self {
+        FirestoreProjectRepo { db }
+    }
+}
+
+#[async_trait]
+impl ProjectRepo for FirestoreProjectRepo {
+    async fn get_project(&self, id: String) -> Result<std::option::Option<Project>, StorageError> {
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(PROJECT_FIRESTORE_COLLECTION)
+            .obj()
+            .one(&id)
+            .await?)
+    }
+
+    async fn save_project(&self, project: &Project) -> Result<(), StorageError> {
+        self.db
+            .fluent()
+            .insert()
+            .into(PROJECT_FIRESTORE_COLLECTION)
+            .document_id(&project.id)
+            .object(project)
+            .execute()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_api_key(&self, id: String) -> Result<std::option::Option<ApiKey>, StorageError> {
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(API_KEY_FIRESTORE_COLLECTION)
+            .obj()
+            .one(&id)
+            .await?)
+    }
+
+    async fn save_api_key(&self, api_key: &ApiKey) -> Result<(), StorageError> {
+        self.db
+            .fluent()
+            .insert()
+            .into(API_KEY_FIRESTORE_COLLECTION)
+            .document_id(&api_key.id)
+            .object(api_key)
+            .execute()
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Postgres-backed `ProjectRepo`, built on a pooled `sqlx::PgPool`.
+///
+/// Expects `projects(id text primary key, name text not null, min_votes double precision not null,
+/// created_at timestamptz not null, deleted_at timestamptz)` and `api_keys(id text primary key,
+/// project_id text not null, hash text not null, created_at timestamptz not null)` tables.
+///
+/// Queries are written with the runtime-checked `sqlx::query`/`query_as` rather than the
+/// compile-time `query!`/`query_as!` macros: this crate ships no migrations or offline query
+/// cache, so the macros would require a live, already-migrated database at build time.
+#[derive(Debug, Clone)]
+pub struct PostgresProjectRepo {
+    pool: PgPool,
+}
+
+impl PostgresProjectRepo {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresProjectRepo { pool }
+    }
+}
+
+#[async_trait]
+impl ProjectRepo for PostgresProjectRepo {
+    async fn get_project(&self, id: String) -> Result<std::option::Option<Project>, StorageError> {
+        let project = sqlx::query_as::<_, Project>(
+            r#"SELECT id, name, min_votes, created_at, deleted_at FROM projects WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(project)
+    }
+
+    async fn save_project(&self, project: &Project) -> Result<(), StorageError> {
+        sqlx::query(
+            r#"
+            INSERT INTO projects (id, name, min_votes, created_at, deleted_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (id) DO UPDATE
+            SET name = excluded.name, min_votes = excluded.min_votes, deleted_at = excluded.deleted_at
+            "#,
+        )
+        .bind(&project.id)
+        .bind(&project.name)
+        .bind(project.min_votes)
+        .bind(project.created_at)
+        .bind(project.deleted_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_api_key(&self, id: String) -> Result<std::option::Option<ApiKey>, StorageError> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"SELECT id, project_id, hash, created_at FROM api_keys WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(api_key)
+    }
+
+    async fn save_api_key(&self, api_key: &ApiKey) -> Result<(), StorageError> {
+        sqlx::query(r#"INSERT INTO api_keys (id, project_id, hash, created_at) VALUES ($1, $2, $3, $4)"#)
+            .bind(&api_key.id)
+            .bind(&api_key.project_id)
+            .bind(&api_key.hash)
+            .bind(api_key.created_at)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
+use crate::storage_error::StorageError;
 use chrono::{DateTime, Utc};
-use errors::{FirestoreDataNotFoundError, FirestoreError, FirestoreErrorPublicGenericDetails};
 use firestore::*;
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
@@ -9,7 +10,7 @@ use std::{
 
 static RANK_FIRESTORE_COLLECTION: &str = "ranks";
 
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct Rank {
     /// PK is project_id+item_id, the total length is 42 (21+21) since id are generated via nanoid()
@@ -26,6 +27,19 @@ pub struct Rank {
     pub deleted_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ScoreError {
+    #[error("score {score} is out of bounds [{min}, {max}]")]
+    OutOfBounds { score: f64, min: f64, max: f64 },
+}
+
+/// What can go wrong inside [`RankRepoFirestore::rank`]'s transaction closure, smuggled out
+/// through a side channel since the closure can only return `FirestoreError`.
+enum RankFailure {
+    NotFound,
+    Score(ScoreError),
+}
+
 impl Rank {
     pub fn get_computed_id(&self) -> String {
         format!("{}{}", self.project_id, self.item_id)
@@ -35,19 +49,57 @@ impl Rank {
         self.id = self.get_computed_id();
     }
 
-    // TODO: add error handling to reject if score < min || score > max
-    pub fn update_score(&mut self, score: f64) {
+    pub fn update_score(&mut self, score: f64) -> Result<(), ScoreError> {
+        if score < self.min || score > self.max {
+            return Err(ScoreError::OutOfBounds {
+                score,
+                min: self.min,
+                max: self.max,
+            });
+        }
+
         let old_average = self.average;
         self.average = ((old_average * self.total as f64) + score) / (self.total + 1) as f64;
         self.total += 1;
+
+        Ok(())
     }
+
+    /// Weighted rating `(v/(v+m))*R + (m/(v+m))*C`, pulling low-vote items toward the
+    /// project-wide mean instead of letting a single extreme vote dominate the leaderboard.
+    ///
+    /// `global_mean` is the mean `average` across the project's items (`C`), `min_votes` is the
+    /// prior weight (`m`, e.g. 10) configured per-project.
+    pub fn bayesian_score(&self, global_mean: f64, min_votes: f64) -> f64 {
+        let v = self.total as f64;
+        let m = min_votes;
+
+        (v / (v + m)) * self.average + (m / (v + m)) * global_mean
+    }
+}
+
+/// A page of the `/leaderboard` query, ordered by score descending.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardPage {
+    pub items: Vec<Rank>,
+    /// opaque, pass back as `?cursor=` to fetch the next page; `None` once exhausted
+    pub next_cursor: Option<String>,
 }
 
 #[async_trait]
 pub trait RankRepo: Send + Sync {
-    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, FirestoreError>;
-    async fn save(&self, rank: &Rank) -> Result<(), FirestoreError>;
-    async fn rank(&self, id: String, score: f64) -> Result<(), FirestoreError>;
+    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, StorageError>;
+    async fn save(&self, rank: &Rank) -> Result<(), StorageError>;
+    async fn rank(&self, id: String, score: f64) -> Result<(), StorageError>;
+    /// `min_votes` is the prior weight `m` used by `Rank::bayesian_score`, see `Project::min_votes`.
+    async fn leaderboard(
+        &self,
+        project_id: String,
+        limit: i64,
+        cursor: Option<String>,
+        min_votes: f64,
+    ) -> Result<LeaderboardPage, StorageError>;
 }
 
 #[derive(Debug, Clone, Default)]
@@ -57,11 +109,11 @@ pub struct RankRepoInMemory {
 
 #[async_trait]
 impl RankRepo for RankRepoInMemory {
-    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, FirestoreError> {
+    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, StorageError> {
         Ok(self.map.lock().unwrap().get(&id).cloned())
     }
 
-    async fn save(&self, rank: &Rank) -> Result<(), FirestoreError> {
+    async fn save(&self, rank: &Rank) -> Result<(), StorageError> {
         self.map
             .lock()
             .unwrap()
@@ -71,21 +123,57 @@ impl RankRepo for RankRepoInMemory {
     }
 
     // TODO: test it I am curious to check it works (that we get the HashMap ref)
-    async fn rank(&self, id: String, score: f64) -> Result<(), FirestoreError> {
+    async fn rank(&self, id: String, score: f64) -> Result<(), StorageError> {
         let mut guard = self.map.lock().unwrap();
         let Some(rank) = guard.get_mut(&id) else {
-            return Err(FirestoreError::DataNotFoundError(
-                FirestoreDataNotFoundError::new(
-                    FirestoreErrorPublicGenericDetails::new("5".to_string()), // TODO: better error handling, here 5 comes from gRPC not found
-                    "5".to_string(),
-                ),
-            ));
+            return Err(StorageError::NotFound);
         };
 
-        rank.update_score(score);
+        rank.update_score(score)?;
 
         Ok(())
     }
+
+    async fn leaderboard(
+        &self,
+        project_id: String,
+        limit: i64,
+        cursor: Option<String>,
+        min_votes: f64,
+    ) -> Result<LeaderboardPage, StorageError> {
+        let offset: usize = cursor.and_then(|cursor| cursor.parse().ok()).unwrap_or(0);
+        let limit = limit.max(0) as usize;
+
+        let mut ranks: Vec<Rank> = self
+            .map
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|rank| rank.project_id == project_id)
+            .cloned()
+            .collect();
+
+        let global_mean = if ranks.is_empty() {
+            0.0
+        } else {
+            ranks.iter().map(|rank| rank.average).sum::<f64>() / ranks.len() as f64
+        };
+
+        ranks.sort_by(|a, b| {
+            b.bayesian_score(global_mean, min_votes)
+                .partial_cmp(&a.bayesian_score(global_mean, min_votes))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let items: Vec<Rank> = ranks.iter().skip(offset).take(limit).cloned().collect();
+        let next_cursor = if offset + items.len() < ranks.len() {
+            Some((offset + items.len()).to_string())
+        } else {
+            None
+        };
+
+        Ok(LeaderboardPage { items, next_cursor })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -101,17 +189,18 @@ impl RankRepoFirestore {
 
 #[async_trait]
 impl RankRepo for RankRepoFirestore {
-    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, FirestoreError> {
-        self.db
+    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, StorageError> {
+        Ok(self
+            .db
             .fluent()
             .select()
             .by_id_in(RANK_FIRESTORE_COLLECTION)
             .obj()
             .one(&id)
-            .await
+            .await?)
     }
 
-    async fn save(&self, rank: &Rank) -> Result<(), FirestoreError> {
+    async fn save(&self, rank: &Rank) -> Result<(), StorageError> {
         self.db
             .fluent()
             .insert()
@@ -119,39 +208,338 @@ impl RankRepo for RankRepoFirestore {
             .document_id(&rank.get_computed_id())
             .object(rank)
             .execute()
-            .await
+            .await?;
+
+        Ok(())
     }
 
-    async fn rank(&self, id: String, score: f64) -> Result<(), FirestoreError> {
-        self.db
-            .run_transaction(|db, transaction| {
-                let id: String = id.clone();
-
-                Box::pin(async move {
-                    let mut rank: Rank = db
-                        .fluent()
-                        .select()
-                        .by_id_in(RANK_FIRESTORE_COLLECTION)
-                        .obj()
-                        .one(&id)
-                        .await?
-                        .expect("Missing document"); // TODO: check the 404 is acting like others 404
-
-                    rank.update_score(score);
-
-                    db.fluent()
-                        .update()
-                        .fields(paths ! (Rank::{
-                         average,
-                         total,
-                        }))
-                        .in_col(RANK_FIRESTORE_COLLECTION)
-                        .document_id(&id)
-                        .object(&rank)
-                        .add_to_transaction(transaction)?;
-                    Ok(())
-                })
+    async fn rank(&self, id: String, score: f64) -> Result<(), StorageError> {
+        // `run_transaction`'s closure returns `Result<_, FirestoreError>`, which can't carry our
+        // own errors, so we stash them here instead of panicking and pick them back up once the
+        // transaction has run.
+        let failure: Arc<Mutex<Option<RankFailure>>> = Arc::new(Mutex::new(None));
+
+        let transaction_result = self
+            .db
+            .run_transaction({
+                let failure = failure.clone();
+
+                move |db, transaction| {
+                    let id: String = id.clone();
+                    let failure = failure.clone();
+
+                    Box::pin(async move {
+                        let Some(mut rank): Option<Rank> = db
+                            .fluent()
+                            .select()
+                            .by_id_in(RANK_FIRESTORE_COLLECTION)
+                            .obj()
+                            .one(&id)
+                            .await?
+                        else {
+                            *failure.lock().unwrap() = Some(RankFailure::NotFound);
+                            return Ok(());
+                        };
+
+                        if let Err(err) = rank.update_score(score) {
+                            *failure.lock().unwrap() = Some(RankFailure::Score(err));
+                            return Ok(());
+                        }
+
+                        db.fluent()
+                            .update()
+                            .fields(paths ! (Rank::{
+                             average,
+                             total,
+                            }))
+                            .in_col(RANK_FIRESTORE_COLLECTION)
+                            .document_id(&id)
+                            .object(&rank)
+                            .add_to_transaction(transaction)?;
+                        Ok(())
+                    })
+                }
             })
+            .await;
+
+        match failure.lock().unwrap().take() {
+            Some(RankFailure::NotFound) => return Err(StorageError::NotFound),
+            Some(RankFailure::Score(err)) => return Err(StorageError::InvalidScore(err)),
+            None => {}
+        }
+
+        transaction_result?;
+
+        Ok(())
+    }
+
+    // `bayesian_score` is a value computed at query time, Firestore can't order by it directly,
+    // so we fetch the project's ranks once and sort/paginate in-process.
+    async fn leaderboard(
+        &self,
+        project_id: String,
+        limit: i64,
+        cursor: Option<String>,
+        min_votes: f64,
+    ) -> Result<LeaderboardPage, StorageError> {
+        let mut ranks: Vec<Rank> = self
+            .db
+            .fluent()
+            .select()
+            .from(RANK_FIRESTORE_COLLECTION)
+            .filter(|q| q.for_all([q.field(path!(Rank::project_id)).eq(&project_id)]))
+            .obj()
+            .query()
+            .await?;
+
+        let global_mean = if ranks.is_empty() {
+            0.0
+        } else {
+            ranks.iter().map(|rank| rank.average).sum::<f64>() / ranks.len() as f64
+        };
+
+        ranks.sort_by(|a, b| {
+            b.bayesian_score(global_mean, min_votes)
+                .partial_cmp(&a.bayesian_score(global_mean, min_votes))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let offset: usize = cursor.and_then(|cursor| cursor.parse().ok()).unwrap_or(0);
+        let limit = limit.max(0) as usize;
+
+        let items: Vec<Rank> = ranks.iter().skip(offset).take(limit).cloned().collect();
+        let next_cursor = if offset + items.len() < ranks.len() {
+            Some((offset + items.len()).to_string())
+        } else {
+            None
+        };
+
+        Ok(LeaderboardPage { items, next_cursor })
+    }
+}
+
+/// Postgres-backed `RankRepo`, built on a pooled `sqlx::PgPool`.
+///
+/// Expects a `ranks(id text primary key, project_id text not null, item_id text not null,
+/// total bigint not null, average double precision not null, min double precision not null,
+/// max double precision not null, created_at timestamptz not null, deleted_at timestamptz)` table.
+///
+/// Queries are written with the runtime-checked `sqlx::query`/`query_as` rather than the
+/// compile-time `query!`/`query_as!` macros: this crate ships no migrations or offline query
+/// cache, so the macros would require a live, already-migrated database at build time.
+#[derive(Debug, Clone)]
+pub struct RankRepoPostgres {
+    pool: PgPool,
+}
+
+impl RankRepoPostgres {
+    pub fn new(pool: PgPool) -> Self {
+        RankRepoPostgres { pool }
+    }
+}
+
+#[async_trait]
+impl RankRepo for RankRepoPostgres {
+    async fn get(&self, id: String) -> Result<std::option::Option<Rank>, StorageError> {
+        let rank = sqlx::query_as::<_, Rank>(
+            r#"SELECT id, project_id, item_id, total, average, min, max, created_at, deleted_at
+               FROM ranks WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(rank)
+    }
+
+    async fn save(&self, rank: &Rank) -> Result<(), StorageError> {
+        sqlx::query(
+            r#"
+            INSERT INTO ranks (id, project_id, item_id, total, average, min, max, created_at, deleted_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (id) DO UPDATE
+            SET total = excluded.total, average = excluded.average, deleted_at = excluded.deleted_at
+            "#,
+        )
+        .bind(&rank.id)
+        .bind(&rank.project_id)
+        .bind(&rank.item_id)
+        .bind(rank.total)
+        .bind(rank.average)
+        .bind(rank.min)
+        .bind(rank.max)
+        .bind(rank.created_at)
+        .bind(rank.deleted_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn rank(&self, id: String, score: f64) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut rank = sqlx::query_as::<_, Rank>(
+            r#"SELECT id, project_id, item_id, total, average, min, max, created_at, deleted_at
+               FROM ranks WHERE id = $1 FOR UPDATE"#,
+        )
+        .bind(&id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(StorageError::NotFound)?;
+
+        rank.update_score(score)?;
+
+        sqlx::query(r#"UPDATE ranks SET average = $2, total = $3 WHERE id = $1"#)
+            .bind(&rank.id)
+            .bind(rank.average)
+            .bind(rank.total)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // `average`/`total` are plain columns, so unlike Firestore we can let Postgres do the
+    // bayesian_score ordering (and the global_mean averaging) instead of sorting in-process.
+    async fn leaderboard(
+        &self,
+        project_id: String,
+        limit: i64,
+        cursor: Option<String>,
+        min_votes: f64,
+    ) -> Result<LeaderboardPage, StorageError> {
+        let limit = limit.max(0);
+        let offset: i64 = cursor.and_then(|cursor| cursor.parse().ok()).unwrap_or(0);
+
+        let global_mean: f64 = sqlx::query_scalar(
+            r#"SELECT COALESCE(AVG(average), 0) FROM ranks WHERE project_id = $1"#,
+        )
+        .bind(&project_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        // fetch one extra row beyond `limit` so we can tell whether a next page actually exists,
+        // instead of assuming one whenever this page happens to come back full
+        let mut items = sqlx::query_as::<_, Rank>(
+            r#"
+            SELECT id, project_id, item_id, total, average, min, max, created_at, deleted_at
+            FROM ranks
+            WHERE project_id = $1
+            ORDER BY (total::double precision / (total::double precision + $4)) * average
+                     + ($4 / (total::double precision + $4)) * $5 DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(&project_id)
+        .bind(limit + 1)
+        .bind(offset)
+        .bind(min_votes)
+        .bind(global_mean)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            Some((offset + limit).to_string())
+        } else {
+            None
+        };
+
+        Ok(LeaderboardPage { items, next_cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_score_rejects_scores_outside_min_max() {
+        let mut rank = Rank {
+            min: 1.0,
+            max: 5.0,
+            ..Default::default()
+        };
+
+        assert!(rank.update_score(0.0).is_err());
+        assert!(rank.update_score(5.1).is_err());
+        assert_eq!(rank.total, 0);
+
+        assert!(rank.update_score(4.0).is_ok());
+        assert_eq!(rank.total, 1);
+        assert_eq!(rank.average, 4.0);
+    }
+
+    #[test]
+    fn bayesian_score_pulls_low_vote_items_toward_the_global_mean() {
+        // the exact scenario this request was meant to fix: a single 5/5 vote no longer
+        // outranks a thousand 4.9 votes once both are pulled toward the global mean.
+        let single_high_vote = Rank {
+            total: 1,
+            average: 5.0,
+            ..Default::default()
+        };
+        let many_slightly_lower_votes = Rank {
+            total: 1000,
+            average: 4.9,
+            ..Default::default()
+        };
+
+        let global_mean = 4.0;
+        let min_votes = 10.0;
+
+        let single_high_vote_score = single_high_vote.bayesian_score(global_mean, min_votes);
+        let many_slightly_lower_votes_score =
+            many_slightly_lower_votes.bayesian_score(global_mean, min_votes);
+
+        // (v/(v+m))*R + (m/(v+m))*C, computed by hand for (v=1, R=5, m=10, C=4)
+        assert!((single_high_vote_score - 4.090909090909091).abs() < 1e-9);
+        // ... and for (v=1000, R=4.9, m=10, C=4) - this is the same formula
+        // `RankRepoPostgres::leaderboard` reimplements in SQL, keep them in sync
+        assert!((many_slightly_lower_votes_score - 4.891089108910891).abs() < 1e-9);
+
+        assert!(many_slightly_lower_votes_score > single_high_vote_score);
+    }
+
+    #[tokio::test]
+    async fn in_memory_leaderboard_paginates_with_a_cursor() {
+        let repo = RankRepoInMemory::default();
+
+        for i in 0..5 {
+            let mut rank = Rank {
+                project_id: "project1".to_string(),
+                item_id: format!("item{i}"),
+                min: 0.0,
+                max: 5.0,
+                ..Default::default()
+            };
+            rank.compute_id();
+            rank.update_score(i as f64).unwrap();
+            repo.save(&rank).await.unwrap();
+        }
+
+        let first_page = repo
+            .leaderboard("project1".to_string(), 2, None, 10.0)
+            .await
+            .unwrap();
+        assert_eq!(first_page.items.len(), 2);
+        assert!(first_page.next_cursor.is_some());
+
+        let second_page = repo
+            .leaderboard("project1".to_string(), 2, first_page.next_cursor, 10.0)
+            .await
+            .unwrap();
+        assert_eq!(second_page.items.len(), 2);
+        assert!(second_page.next_cursor.is_some());
+
+        let last_page = repo
+            .leaderboard("project1".to_string(), 2, second_page.next_cursor, 10.0)
             .await
+            .unwrap();
+        assert_eq!(last_page.items.len(), 1);
+        assert!(last_page.next_cursor.is_none());
     }
 }
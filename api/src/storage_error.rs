@@ -0,0 +1,46 @@
+use crate::rank::ScoreError;
+use firestore::errors::FirestoreError;
+use std::error::Error;
+
+/// Backend-agnostic error returned by every repo trait (`UserRepo`, `RankRepo`, ...).
+///
+/// Concrete drivers (Firestore, Postgres, in-memory) map their own error types into this
+/// enum so the rest of the crate never has to know which backend is in use.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("not found")]
+    NotFound,
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("invalid score: {0}")]
+    InvalidScore(#[from] ScoreError),
+    #[error("backend error: {0}")]
+    Backend(#[source] Box<dyn Error + Send + Sync + 'static>),
+}
+
+impl From<FirestoreError> for StorageError {
+    fn from(err: FirestoreError) -> Self {
+        match err {
+            FirestoreError::DataNotFoundError(_) => StorageError::NotFound,
+            other => StorageError::Backend(Box::new(other)),
+        }
+    }
+}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        if let sqlx::Error::RowNotFound = err {
+            return StorageError::NotFound;
+        }
+
+        // unique_violation: surface duplicate-key inserts (e.g. `ProjectRepo::save_api_key`) as a
+        // `Conflict` instead of a generic backend error.
+        if let Some(db_err) = err.as_database_error() {
+            if db_err.code().as_deref() == Some("23505") {
+                return StorageError::Conflict(db_err.message().to_string());
+            }
+        }
+
+        StorageError::Backend(Box::new(err))
+    }
+}
@@ -0,0 +1,131 @@
+use crate::storage_error::StorageError;
+use chrono::{DateTime, Utc};
+use firestore::*;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// the output to our `create_user` handler
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, StorageError>;
+
+    async fn save_user(&self, user: &User) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryUserRepo {
+    map: Arc<Mutex<HashMap<String, User>>>,
+}
+
+#[async_trait]
+impl UserRepo for InMemoryUserRepo {
+    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, StorageError> {
+        Result::Ok(self.map.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), StorageError> {
+        self.map
+            .lock()
+            .unwrap()
+            .insert(user.id.clone(), user.clone());
+
+        Result::Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirestoreUserRepo {
+    pub collection_name: Arc<String>,
+    pub db: Arc<FirestoreDb>,
+}
+
+#[async_trait]
+impl UserRepo for FirestoreUserRepo {
+    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, StorageError> {
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(&self.collection_name)
+            .obj()
+            .one(&id)
+            .await?)
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), StorageError> {
+        self.db
+            .fluent()
+            .insert()
+            .into(&self.collection_name)
+            .document_id(&user.id)
+            .object(user)
+            .execute()
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Postgres-backed `UserRepo`, built on a pooled `sqlx::PgPool`.
+///
+/// Expects a `users(id text primary key, username text not null, created_at timestamptz not null,
+/// deleted_at timestamptz)` table. Queries are written with the runtime-checked `sqlx::query`/
+/// `query_as` rather than the compile-time `query!`/`query_as!` macros: this crate ships no
+/// migrations or offline query cache, so the macros would require a live, already-migrated
+/// database at build time.
+#[derive(Debug, Clone)]
+pub struct PostgresUserRepo {
+    pool: PgPool,
+}
+
+impl PostgresUserRepo {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresUserRepo { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepo for PostgresUserRepo {
+    async fn get_user(&self, id: String) -> Result<std::option::Option<User>, StorageError> {
+        let user = sqlx::query_as::<_, User>(
+            r#"SELECT id, username, created_at, deleted_at FROM users WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), StorageError> {
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, username, created_at, deleted_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO UPDATE
+            SET username = excluded.username, deleted_at = excluded.deleted_at
+            "#,
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(user.created_at)
+        .bind(user.deleted_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}